@@ -0,0 +1,306 @@
+//! FFI-friendly bridge for driving a shutdown-aware future without a Rust executor.
+//!
+//! Mirrors the scheme uniffi uses for its `RustFuture`: the host owns an opaque
+//! [`FfiShutdownFuture`] handle and drives it through [`poll_with_continuation`]. If the future is
+//! not immediately ready, a relay [`Waker`] is installed that re-polls the handle and invokes the
+//! continuation itself the moment the future can make progress, so the host never needs to poll
+//! again on its own and never needs an async runtime of its own.
+//!
+//! A `Waker` may legitimately be woken from any thread, possibly concurrently, after the host has
+//! already released its own handle, so [`FfiShutdownFuture`] is reference-counted: every in-flight
+//! drive holds its own [`Arc`] strong reference (taken out via [`Arc::increment_strong_count`]
+//! without disturbing the host's), and [`free`]/[`cancel`] only drop the host's single reference
+//! rather than unconditionally deallocating a bare pointer. The handle is only ever actually freed
+//! once the last strong reference - host or in-flight drive - goes away.
+
+use std::ffi::c_void;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use crate::shutdown_signal::ShutdownSignal;
+use crate::wrap_cancel::WrapCancel;
+
+/// Outcome passed to the [`Continuation`] once the driven future resolves.
+#[repr(C)]
+pub enum PollResult {
+	/// The wrapped future completed before the shutdown was triggered.
+	Completed,
+	/// The wrapped future was cancelled because the shutdown was triggered.
+	Cancelled,
+}
+
+/// Host-provided callback, invoked exactly once with the terminal [`PollResult`], or never if the
+/// handle is released with [`cancel`] before the future resolves.
+///
+/// `data` is the opaque pointer the host passed to [`poll_with_continuation`]; it is handed back
+/// unchanged so the host can recover its own context from it.
+pub type Continuation = extern "C" fn(data: *mut c_void, result: PollResult);
+
+/// Opaque handle to a future driven across the FFI boundary.
+///
+/// Owns a type-erased, boxed future that resolves to [`PollResult`]. The wrapped future's own
+/// output is discarded, since arbitrary Rust output types have no FFI representation; only
+/// whether it completed or was cancelled crosses the boundary.
+///
+/// Build one with [`FfiShutdownFuture::from_wrap_cancel`] or
+/// [`FfiShutdownFuture::from_shutdown_signal`], turn it into a pointer with
+/// [`FfiShutdownFuture::into_raw`], drive it with [`poll_with_continuation`], and release it with
+/// [`free`] or [`cancel`].
+pub struct FfiShutdownFuture {
+	/// Guards every poll of `future` so that two concurrent wakes can never poll it at once.
+	///
+	/// `None` once the future has resolved or [`cancel`] has dropped it early; driving the handle
+	/// then simply has nothing left to do.
+	future: Mutex<Option<Pin<Box<dyn Future<Output = PollResult> + Send>>>>,
+}
+
+impl FfiShutdownFuture {
+	/// Wrap a [`WrapCancel`] future for driving across the FFI boundary.
+	pub fn from_wrap_cancel<T, F>(future: WrapCancel<T, F>) -> Self
+	where
+		T: Clone + Send + 'static,
+		F: Future + Send + 'static,
+	{
+		Self::from_future(async move {
+			match future.await {
+				Ok(_) => PollResult::Completed,
+				Err(_) => PollResult::Cancelled,
+			}
+		})
+	}
+
+	/// Wrap a bare [`ShutdownSignal`] for driving across the FFI boundary.
+	///
+	/// Always resolves to [`PollResult::Cancelled`] once the shutdown is triggered, since there is
+	/// no wrapped future that could complete instead.
+	pub fn from_shutdown_signal<T>(signal: ShutdownSignal<T>) -> Self
+	where
+		T: Clone + Send + 'static,
+	{
+		Self::from_future(async move {
+			signal.await;
+			PollResult::Cancelled
+		})
+	}
+
+	fn from_future<Fut>(future: Fut) -> Self
+	where
+		Fut: Future<Output = PollResult> + Send + 'static,
+	{
+		Self {
+			future: Mutex::new(Some(Box::pin(future))),
+		}
+	}
+
+	/// Wrap this handle in an [`Arc`] and leak it as a raw pointer for handing across the FFI
+	/// boundary.
+	///
+	/// The returned pointer carries one strong reference and must eventually be passed to
+	/// [`free`] or [`cancel`] exactly once to release it.
+	pub fn into_raw(self) -> *const FfiShutdownFuture {
+		Arc::into_raw(Arc::new(self))
+	}
+}
+
+/// A task driving a single [`FfiShutdownFuture`] to completion.
+///
+/// Reference-counted so that the relay [`Waker`] can keep it alive between polls without the host
+/// having to manage its lifetime. Holds its own strong reference to `handle`, independent of the
+/// host's, so a [`free`]/[`cancel`] that runs while a drive is in flight can never pull the handle
+/// out from under it.
+struct Task {
+	handle: Arc<FfiShutdownFuture>,
+	continuation: Continuation,
+	data: *mut c_void,
+}
+
+// SAFETY: The host is required to treat `data` as exclusively owned by the task for as long as it
+// is alive, so it is sound to move the task across threads.
+unsafe impl Send for Task {}
+unsafe impl Sync for Task {}
+
+/// Poll `handle` once, invoking `continuation` as soon as a result is available.
+///
+/// If the wrapped future is immediately ready, `continuation` is called before this function
+/// returns. Otherwise a relay waker is installed that re-polls `handle` and calls `continuation`
+/// itself the moment the future is woken; the host does not need to call this function again.
+///
+/// # Safety
+///
+/// `handle` must be a pointer obtained from [`FfiShutdownFuture::into_raw`] that has not yet been
+/// passed to [`free`] or [`cancel`]. `data` must remain valid until `continuation` has been called
+/// or `handle` is released, and the host must treat it as exclusively owned by the drive until
+/// then: `continuation` may be invoked with it from whichever thread wakes the future.
+pub unsafe fn poll_with_continuation(handle: *const FfiShutdownFuture, continuation: Continuation, data: *mut c_void) {
+	// SAFETY: `handle` carries a strong reference the host still owns; incrementing the count
+	// before taking our own `Arc` out of it borrows that reference instead of consuming it, so the
+	// host's pointer is still valid to pass to `free`/`cancel` afterwards.
+	let handle = unsafe {
+		Arc::increment_strong_count(handle);
+		Arc::from_raw(handle)
+	};
+	drive(Arc::new(Task { handle, continuation, data }));
+}
+
+/// Poll the future owned by `task`, calling its continuation on completion or rearming the relay
+/// waker for the next wake-up otherwise.
+///
+/// Does nothing if the future has already resolved or was dropped early by [`cancel`]; otherwise
+/// holds the handle's mutex for the whole poll so that two concurrent wakes of the same handle can
+/// never race on it.
+fn drive(task: Arc<Task>) {
+	let mut future = task.handle.future.lock().unwrap_or_else(|e| e.into_inner());
+	let Some(pinned) = future.as_mut() else {
+		return;
+	};
+
+	let waker = relay_waker(task.clone());
+	let mut context = Context::from_waker(&waker);
+
+	if let Poll::Ready(result) = pinned.as_mut().poll(&mut context) {
+		*future = None;
+		drop(future);
+		(task.continuation)(task.data, result);
+	}
+}
+
+/// Build a [`Waker`] that re-drives `task` when woken, relaying progress back to the host.
+fn relay_waker(task: Arc<Task>) -> Waker {
+	let raw = Arc::into_raw(task) as *const ();
+	// SAFETY: `VTABLE` only ever operates on pointers produced by `Arc::into_raw` above.
+	unsafe { Waker::from_raw(RawWaker::new(raw, &VTABLE)) }
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(waker_clone, waker_wake, waker_wake_by_ref, waker_drop);
+
+unsafe fn waker_clone(data: *const ()) -> RawWaker {
+	let task = Arc::from_raw(data as *const Task);
+	let cloned = task.clone();
+	std::mem::forget(task);
+	RawWaker::new(Arc::into_raw(cloned) as *const (), &VTABLE)
+}
+
+unsafe fn waker_wake(data: *const ()) {
+	let task = Arc::from_raw(data as *const Task);
+	drive(task);
+}
+
+unsafe fn waker_wake_by_ref(data: *const ()) {
+	let task = Arc::from_raw(data as *const Task);
+	drive(task.clone());
+	std::mem::forget(task);
+}
+
+unsafe fn waker_drop(data: *const ()) {
+	drop(Arc::from_raw(data as *const Task));
+}
+
+/// Release a handle that has already resolved (i.e. after its continuation was called).
+///
+/// Drops the host's strong reference to `handle`. If a drive is still concurrently in flight
+/// (e.g. a stray wake-up racing this call), the handle is only actually deallocated once that
+/// drive's own reference is dropped too.
+///
+/// # Safety
+///
+/// `handle` must be a pointer obtained from [`FfiShutdownFuture::into_raw`] that has not already
+/// been passed to [`free`] or [`cancel`].
+pub unsafe fn free(handle: *const FfiShutdownFuture) {
+	// SAFETY: `handle` carries the host's one strong reference; reconstructing the `Arc` from it
+	// and dropping it releases exactly that reference.
+	drop(unsafe { Arc::from_raw(handle) });
+}
+
+/// Release a handle before it has resolved, dropping the wrapped future early.
+///
+/// This drops the underlying [`WrapCancel`]/[`ShutdownSignal`] future without waiting for the
+/// shutdown, exactly like dropping it would if it were owned directly from Rust - regardless of
+/// whether a drive is concurrently in flight, since the future is cleared under the same mutex
+/// that every drive polls through.
+///
+/// # Safety
+///
+/// Same requirements as [`free`].
+pub unsafe fn cancel(handle: *const FfiShutdownFuture) {
+	// SAFETY: see `free`.
+	let handle = unsafe { Arc::from_raw(handle) };
+	*handle.future.lock().unwrap_or_else(|e| e.into_inner()) = None;
+	drop(handle);
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::Cell;
+	use std::ffi::c_void;
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	use super::*;
+
+	thread_local! {
+		static LAST_RESULT: Cell<Option<bool>> = const { Cell::new(None) };
+	}
+
+	extern "C" fn record_result(_data: *mut c_void, result: PollResult) {
+		LAST_RESULT.with(|cell| cell.set(Some(matches!(result, PollResult::Completed))));
+	}
+
+	fn take_result() -> Option<bool> {
+		LAST_RESULT.with(|cell| cell.take())
+	}
+
+	#[test]
+	fn poll_with_continuation_reports_completion() {
+		let handle = FfiShutdownFuture::from_future(async { PollResult::Completed }).into_raw();
+
+		// SAFETY: `handle` was just created and is not used after this call.
+		unsafe {
+			poll_with_continuation(handle, record_result, std::ptr::null_mut());
+			assert_eq!(take_result(), Some(true));
+			free(handle);
+		}
+	}
+
+	#[test]
+	fn poll_with_continuation_reports_cancellation() {
+		let handle = FfiShutdownFuture::from_future(async { PollResult::Cancelled }).into_raw();
+
+		// SAFETY: `handle` was just created and is not used after this call.
+		unsafe {
+			poll_with_continuation(handle, record_result, std::ptr::null_mut());
+			assert_eq!(take_result(), Some(false));
+			free(handle);
+		}
+	}
+
+	#[test]
+	fn cancel_drops_the_wrapped_future_without_calling_the_continuation() {
+		let dropped = Arc::new(AtomicBool::new(false));
+		let guard = DropFlag(dropped.clone());
+
+		let handle = FfiShutdownFuture::from_future(async move {
+			let _guard = guard;
+			std::future::pending::<()>().await;
+			PollResult::Completed
+		})
+		.into_raw();
+
+		// SAFETY: `handle` was just created and is not used after this call.
+		unsafe {
+			poll_with_continuation(handle, record_result, std::ptr::null_mut());
+			assert_eq!(take_result(), None);
+			cancel(handle);
+		}
+
+		assert!(dropped.load(Ordering::SeqCst));
+	}
+
+	struct DropFlag(Arc<AtomicBool>);
+
+	impl Drop for DropFlag {
+		fn drop(&mut self) {
+			self.0.store(true, Ordering::SeqCst);
+		}
+	}
+}
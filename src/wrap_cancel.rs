@@ -2,19 +2,72 @@ use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+use futures_core::future::FusedFuture;
+use pin_project_lite::pin_project;
+
 use crate::shutdown_signal::ShutdownSignal;
 
-/// Wrapped future that is automatically cancelled when a shutdown is triggered.
-///
-/// If the wrapped future completes before the shutdown is triggered,
-/// the output of the original future is yielded as `Ok(value)`.
-///
-/// If the shutdown is triggered before the wrapped future completes,
-/// the original future is dropped and the shutdown reason is yielded as `Err(shutdown_reason)`.
-#[must_use = "futures must be polled to make progress"]
-pub struct WrapCancel<T: Clone, F> {
-	pub(crate) shutdown_signal: ShutdownSignal<T>,
-	pub(crate) future: Result<F, T>,
+pin_project! {
+	/// The internal state of a [`WrapCancel`] future.
+	#[project = StateProj]
+	pub(crate) enum State<F, T> {
+		/// The wrapped future has not resolved yet.
+		Pending { #[pin] future: F },
+
+		/// The shutdown signal fired before the wrapped future completed.
+		///
+		/// The reason is kept around so that polling again after this point keeps
+		/// yielding the same reason instead of touching the (already dropped) wrapped future.
+		Cancelled { reason: T },
+
+		/// The wrapped future completed on its own; its output has already been
+		/// handed back and must not be produced again.
+		Terminated,
+	}
+}
+
+pin_project! {
+	/// Wrapped future that is automatically cancelled when a shutdown is triggered.
+	///
+	/// If the wrapped future completes before the shutdown is triggered,
+	/// the output of the original future is yielded as `Ok(value)`.
+	///
+	/// If the shutdown is triggered before the wrapped future completes,
+	/// the original future is dropped and the shutdown reason is yielded as `Err(shutdown_reason)`.
+	#[must_use = "futures must be polled to make progress"]
+	pub struct WrapCancel<T: Clone, F: Future> {
+		pub(crate) shutdown_signal: ShutdownSignal<T>,
+		#[pin]
+		pub(crate) future: State<F, T>,
+		pub(crate) cancel_hook: Option<Box<dyn FnOnce(&T) + Send>>,
+		pub(crate) complete_hook: Option<Box<dyn FnOnce(&F::Output) + Send>>,
+	}
+}
+
+impl<T: Clone, F: Future> WrapCancel<T, F> {
+	/// Register a closure that runs exactly once if the wrapped future is cancelled by a shutdown.
+	///
+	/// The closure is invoked from within `poll()`, right before the shutdown reason is yielded.
+	/// It is never called if the wrapped future completes on its own.
+	pub fn with_cancel_hook<C>(mut self, hook: C) -> Self
+	where
+		C: FnOnce(&T) + Send + 'static,
+	{
+		self.cancel_hook = Some(Box::new(hook));
+		self
+	}
+
+	/// Register a closure that runs exactly once if the wrapped future completes before a shutdown is triggered.
+	///
+	/// The closure is invoked from within `poll()`, right before the output is yielded.
+	/// It is never called if the wrapped future is cancelled by a shutdown.
+	pub fn with_complete_hook<C>(mut self, hook: C) -> Self
+	where
+		C: FnOnce(&F::Output) + Send + 'static,
+	{
+		self.complete_hook = Some(Box::new(hook));
+		self
+	}
 }
 
 impl<T: Clone, F: Future> Future for WrapCancel<T, F> {
@@ -22,30 +75,108 @@ impl<T: Clone, F: Future> Future for WrapCancel<T, F> {
 
 	#[inline]
 	fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
-		// SAFETY: We never move `future`, so we can not violate the requirements of `F`.
-		let me = unsafe { self.get_unchecked_mut() };
-
-		// SAFETY: We never move `future`, so we can not violate the requirements of `F`.
-		// We do drop it, but that's fine.
-		match &mut me.future {
-			Err(e) => return Poll::Ready(Err(e.clone())),
-			Ok(future) => {
-				let future = unsafe { Pin::new_unchecked(future) };
+		let mut this = self.project();
+
+		match this.future.as_mut().project() {
+			StateProj::Terminated => panic!("WrapCancel polled after it already returned Poll::Ready"),
+			StateProj::Cancelled { reason } => return Poll::Ready(Err(reason.clone())),
+			StateProj::Pending { future } => {
 				if let Poll::Ready(value) = future.poll(context) {
+					this.future.set(State::Terminated);
+					if let Some(hook) = this.complete_hook.take() {
+						hook(&value);
+					}
 					return Poll::Ready(Ok(value));
 				}
 			},
 		}
 
 		// Otherwise check if the shutdown signal has been given.
-		let shutdown = Pin::new(&mut me.shutdown_signal)
-			.poll(context);
+		let shutdown = Pin::new(this.shutdown_signal).poll(context);
 		match shutdown {
 			Poll::Ready(reason) => {
-				me.future = Err(reason.clone());
+				this.future.set(State::Cancelled { reason: reason.clone() });
+				if let Some(hook) = this.cancel_hook.take() {
+					hook(&reason);
+				}
 				Poll::Ready(Err(reason))
 			},
 			Poll::Pending => Poll::Pending,
 		}
 	}
 }
+
+impl<T: Clone, F: Future> FusedFuture for WrapCancel<T, F> {
+	#[inline]
+	fn is_terminated(&self) -> bool {
+		!matches!(self.future, State::Pending { .. })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::future::Future;
+	use std::pin::Pin;
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use std::sync::Arc;
+	use std::task::{Context, Poll, Waker};
+
+	use futures_core::future::FusedFuture;
+
+	use super::WrapCancel;
+	use crate::ShutdownManager;
+
+	fn poll_once<F: Future>(future: Pin<&mut F>) -> Poll<F::Output> {
+		let mut context = Context::from_waker(Waker::noop());
+		future.poll(&mut context)
+	}
+
+	#[test]
+	#[should_panic(expected = "WrapCancel polled after it already returned Poll::Ready")]
+	fn poll_after_complete_panics() {
+		let manager = ShutdownManager::<&'static str>::new();
+		let mut wrapped = Box::pin(manager.wrap_cancel(async { 1 }));
+
+		assert!(matches!(poll_once(wrapped.as_mut()), Poll::Ready(Ok(1))));
+		assert!(wrapped.is_terminated());
+
+		// Polling again after completion is a contract violation: it must panic rather than
+		// silently re-polling the already-finished wrapped future.
+		let _ = poll_once(wrapped.as_mut());
+	}
+
+	#[test]
+	fn poll_after_cancel_returns_cached_reason_and_fires_hook_once() {
+		let manager = ShutdownManager::<&'static str>::new();
+		let hook_fired = Arc::new(AtomicBool::new(false));
+		let hook_fired_clone = hook_fired.clone();
+
+		let mut wrapped = Box::pin(
+			manager
+				.wrap_cancel(std::future::pending::<()>())
+				.with_cancel_hook(move |_reason| hook_fired_clone.store(true, Ordering::SeqCst)),
+		);
+
+		manager.trigger_shutdown("bye");
+
+		assert!(matches!(poll_once(wrapped.as_mut()), Poll::Ready(Err("bye"))));
+		assert!(hook_fired.load(Ordering::SeqCst));
+		assert!(wrapped.is_terminated());
+
+		// Unlike polling after completion, polling again after a cancellation is tolerated and
+		// keeps yielding the same cached reason, without firing the hook a second time.
+		hook_fired.store(false, Ordering::SeqCst);
+		assert!(matches!(poll_once(wrapped.as_mut()), Poll::Ready(Err("bye"))));
+		assert!(!hook_fired.load(Ordering::SeqCst));
+	}
+
+	fn assert_unpin<T: Unpin>() {}
+
+	#[test]
+	fn wrap_cancel_is_unpin_when_wrapped_future_is_unpin() {
+		// `pin_project_lite` only derives `Unpin` for `WrapCancel` when every `#[pin]` field is
+		// `Unpin`, which for `State` means the wrapped future itself; pin it down with a compile-time
+		// check so a future change to the projection can't silently regress it.
+		assert_unpin::<WrapCancel<&'static str, std::future::Ready<()>>>();
+	}
+}